@@ -1,9 +1,44 @@
-use std::sync::atomic::{AtomicUsize, Ordering};
-use std::sync::RwLock;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock};
 use std::time::{Duration, Instant};
 
 use crate::config::Config;
 
+/// Counters shared between the proxy and the TUI so the status bar can reflect
+/// upstream health (retries issued while the target boots, and timeouts).
+#[derive(Default)]
+pub struct Metrics {
+    retries: AtomicU64,
+    timeouts: AtomicU64,
+    relay_connected: AtomicBool,
+}
+
+impl Metrics {
+    pub fn record_retry(&self) {
+        self.retries.fetch_add(1, Ordering::SeqCst);
+    }
+
+    pub fn record_timeout(&self) {
+        self.timeouts.fetch_add(1, Ordering::SeqCst);
+    }
+
+    pub fn retries(&self) -> u64 {
+        self.retries.load(Ordering::SeqCst)
+    }
+
+    pub fn timeouts(&self) -> u64 {
+        self.timeouts.load(Ordering::SeqCst)
+    }
+
+    pub fn set_relay_connected(&self, connected: bool) {
+        self.relay_connected.store(connected, Ordering::SeqCst);
+    }
+
+    pub fn relay_connected(&self) -> bool {
+        self.relay_connected.load(Ordering::SeqCst)
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum AppState {
     Starting,
@@ -18,19 +53,25 @@ pub struct App {
     logs: RwLock<Vec<(Instant, String)>>,
     request_count: AtomicUsize,
     config: Config,
+    metrics: Arc<Metrics>,
 }
 
 impl App {
-    pub fn new(config: Config) -> Self {
+    pub fn new(config: Config, metrics: Arc<Metrics>) -> Self {
         Self {
             title: String::from("chopd-rs"),
             state: RwLock::new(AppState::Starting),
             logs: RwLock::new(Vec::new()),
             request_count: AtomicUsize::new(0),
             config,
+            metrics,
         }
     }
 
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
     pub fn state(&self) -> AppState {
         self.state.read().unwrap().clone()
     }
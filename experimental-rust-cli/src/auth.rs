@@ -0,0 +1,140 @@
+use k256::ecdsa::{RecoveryId, Signature, VerifyingKey};
+use sha3::{Digest, Keccak256};
+
+use crate::config::AuthMode;
+
+/// Result of verifying a request's claimed identity.
+#[derive(Debug, Clone)]
+pub enum VerifyOutcome {
+    /// Verification was not attempted (auth is off). The claimed address, if
+    /// any, is trusted as-is — suitable for `/_chopin/login` dev flows.
+    Skipped,
+    /// The signature recovered to `address`, matching what the client claimed.
+    Verified { address: String },
+    /// Verification was required but failed; the handler must reply `401`.
+    Rejected { reason: String },
+}
+
+/// Verifies the signature carried on a request against the claimed address.
+///
+/// The signed payload is the canonical message `method\npath\nbody_hash\nnonce`
+/// wrapped in the Ethereum `personal_sign` envelope, so ordinary wallet
+/// signatures verify without any custom client code. In [`AuthMode::Off`] this
+/// is a no-op and the caller keeps trusting the `dev-address` cookie.
+///
+/// This function only proves authenticity, not freshness: the `nonce` is bound
+/// into the signature but anti-replay (freshness window + single-use) is the
+/// caller's responsibility — see `ProxyServer::check_nonce`.
+pub fn verify_request(
+    mode: &AuthMode,
+    claimed: Option<&str>,
+    method: &str,
+    path: &str,
+    body: &[u8],
+    signature_hex: Option<&str>,
+    nonce: Option<&str>,
+) -> VerifyOutcome {
+    if matches!(mode, AuthMode::Off) {
+        return VerifyOutcome::Skipped;
+    }
+
+    let claimed = match claimed {
+        Some(addr) => addr,
+        None => {
+            return VerifyOutcome::Rejected {
+                reason: "no claimed address".to_string(),
+            }
+        }
+    };
+    let signature_hex = match signature_hex {
+        Some(sig) => sig,
+        None => {
+            return VerifyOutcome::Rejected {
+                reason: "missing signature".to_string(),
+            }
+        }
+    };
+    let nonce = nonce.unwrap_or("");
+
+    let message = canonical_message(method, path, body, nonce);
+    match recover_address(&message, signature_hex) {
+        Ok(recovered) if addresses_match(&recovered, claimed) => {
+            VerifyOutcome::Verified { address: recovered }
+        }
+        Ok(recovered) => VerifyOutcome::Rejected {
+            reason: format!("address mismatch: recovered {}", recovered),
+        },
+        Err(reason) => VerifyOutcome::Rejected { reason },
+    }
+}
+
+/// Builds the canonical message that clients are expected to sign.
+fn canonical_message(method: &str, path: &str, body: &[u8], nonce: &str) -> String {
+    let body_hash = hex_encode(&Keccak256::digest(body));
+    format!("{}\n{}\n{}\n{}", method, path, body_hash, nonce)
+}
+
+/// Recovers the Ethereum address that produced a `personal_sign` signature.
+fn recover_address(message: &str, signature_hex: &str) -> Result<String, String> {
+    let sig_bytes = hex_decode(signature_hex).map_err(|_| "signature not hex".to_string())?;
+    if sig_bytes.len() != 65 {
+        return Err(format!("expected 65-byte signature, got {}", sig_bytes.len()));
+    }
+
+    // The trailing byte is the recovery id (27/28 in the Ethereum convention).
+    let recovery_id = RecoveryId::from_byte(normalize_recovery(sig_bytes[64]))
+        .ok_or_else(|| "invalid recovery id".to_string())?;
+    let signature =
+        Signature::from_slice(&sig_bytes[..64]).map_err(|_| "malformed signature".to_string())?;
+
+    let digest = eip191_digest(message);
+    let verifying_key =
+        VerifyingKey::recover_from_prehash(&digest, &signature, recovery_id)
+            .map_err(|_| "recovery failed".to_string())?;
+
+    Ok(address_from_key(&verifying_key))
+}
+
+/// Hashes a message with the `\x19Ethereum Signed Message:` prefix.
+fn eip191_digest(message: &str) -> [u8; 32] {
+    let prefixed = format!("\x19Ethereum Signed Message:\n{}{}", message.len(), message);
+    Keccak256::digest(prefixed.as_bytes()).into()
+}
+
+/// Derives the 20-byte Ethereum address (`0x`-prefixed, lowercase) from a key.
+fn address_from_key(key: &VerifyingKey) -> String {
+    let point = key.to_encoded_point(false);
+    // Drop the leading 0x04 tag byte before hashing the public key.
+    let hash = Keccak256::digest(&point.as_bytes()[1..]);
+    format!("0x{}", hex_encode(&hash[12..]))
+}
+
+fn addresses_match(a: &str, b: &str) -> bool {
+    a.trim_start_matches("0x").eq_ignore_ascii_case(b.trim_start_matches("0x"))
+}
+
+fn normalize_recovery(v: u8) -> u8 {
+    match v {
+        27 | 28 => v - 27,
+        other => other,
+    }
+}
+
+fn hex_encode(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len() * 2);
+    for b in bytes {
+        out.push_str(&format!("{:02x}", b));
+    }
+    out
+}
+
+fn hex_decode(s: &str) -> Result<Vec<u8>, ()> {
+    let s = s.trim_start_matches("0x");
+    if s.len() % 2 != 0 {
+        return Err(());
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&s[i..i + 2], 16).map_err(|_| ()))
+        .collect()
+}
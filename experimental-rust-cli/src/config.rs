@@ -38,6 +38,44 @@ pub struct Config {
     /// Environment variables to pass to the target process
     #[serde(default)]
     pub env: HashMap<String, String>,
+
+    /// How incoming identities are authenticated before `x-address` is set.
+    #[serde(default)]
+    pub auth: AuthMode,
+
+    /// Timeout (ms) for establishing a connection to the target server.
+    #[serde(default = "default_connect_timeout")]
+    pub connect_timeout: u64,
+
+    /// Timeout (ms) for a full upstream request/response round-trip.
+    #[serde(default = "default_request_timeout")]
+    pub request_timeout: u64,
+
+    /// Set when invoked via the `replay` subcommand: re-issue the persisted log
+    /// against a fresh target instead of starting the proxy. Not persisted.
+    #[serde(skip)]
+    pub replay: bool,
+
+    /// Public relay endpoint to dial out to. When set, chopd opens a single
+    /// long-lived connection and serves inbound requests over it, so the local
+    /// app is reachable without any inbound port.
+    #[serde(default)]
+    pub relay_url: Option<String>,
+
+    /// Token sent to the relay in an `auth` message once the tunnel connects.
+    #[serde(default)]
+    pub relay_token: Option<String>,
+}
+
+/// Controls whether signed identities are verified before they are trusted.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum AuthMode {
+    /// Trust the `dev-address` cookie verbatim (default; dev login flows).
+    #[default]
+    Off,
+    /// Require a valid wallet signature over the request before trusting it.
+    Strict,
 }
 
 fn default_proxy_port() -> u16 {
@@ -48,26 +86,34 @@ fn default_target_port() -> u16 {
     3000
 }
 
+fn default_connect_timeout() -> u64 {
+    5_000
+}
+
+fn default_request_timeout() -> u64 {
+    30_000
+}
+
 impl Config {
     pub fn new() -> Result<Self> {
         let args = Args::parse();
         
         // Handle 'init' command
-        if let Some(cmd) = args.command {
-            if cmd == "init" {
-                return Self::init();
-            }
+        if args.command.as_deref() == Some("init") {
+            return Self::init();
         }
-        
+        // The 'replay' command re-issues the persisted log instead of serving.
+        let replay = args.command.as_deref() == Some("replay");
+
         // Try to load config file
         let config_path = PathBuf::from("chopin.config.json");
         if config_path.exists() {
             let config_str = fs::read_to_string(&config_path)
                 .context("Failed to read config file")?;
-            
+
             let mut config: Config = serde_json::from_str(&config_str)
                 .context("Failed to parse config file")?;
-            
+
             // Override with command line args if provided
             if let Some(proxy_port) = args.proxy_port {
                 config.proxy_port = proxy_port;
@@ -75,16 +121,23 @@ impl Config {
             if let Some(target_port) = args.target_port {
                 config.target_port = target_port;
             }
-            
+            config.replay = replay;
+
             return Ok(config);
         }
-        
+
         // No config file, use defaults with command line args
         Ok(Config {
             command: String::from("npm run dev"),
             proxy_port: args.proxy_port.unwrap_or(4000),
             target_port: args.target_port.unwrap_or(3000),
             env: HashMap::new(),
+            auth: AuthMode::Off,
+            connect_timeout: default_connect_timeout(),
+            request_timeout: default_request_timeout(),
+            replay,
+            relay_url: None,
+            relay_token: None,
         })
     }
     
@@ -98,6 +151,12 @@ impl Config {
             proxy_port: 4000,
             target_port: 3000,
             env: HashMap::new(),
+            auth: AuthMode::Off,
+            connect_timeout: default_connect_timeout(),
+            request_timeout: default_request_timeout(),
+            replay: false,
+            relay_url: None,
+            relay_token: None,
         };
         
         // Write config file
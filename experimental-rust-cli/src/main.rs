@@ -13,11 +13,12 @@ use tokio::sync::mpsc;
 use tracing::{info, warn};
 
 mod app;
+mod auth;
 mod config;
 mod proxy;
 mod tui;
 
-use app::{App, AppState};
+use app::{App, AppState, Metrics};
 use config::Config;
 use proxy::ProxyServer;
 use tui::ui;
@@ -30,6 +31,12 @@ async fn main() -> Result<()> {
     // Parse command line args and config
     let config = Config::new()?;
 
+    // The `replay` subcommand re-issues the persisted log and exits, without
+    // spinning up the proxy or the TUI.
+    if config.replay {
+        return ProxyServer::replay(&config).await;
+    }
+
     // Setup terminal
     enable_raw_mode()?;
     let mut stdout = io::stdout();
@@ -39,15 +46,18 @@ async fn main() -> Result<()> {
 
     // Create channels for proxy server communication
     let (tx, rx) = mpsc::channel(100);
-    
+
+    // Metrics shared between the proxy and the TUI status bar.
+    let metrics = Arc::new(Metrics::default());
+
     // Initialize the application state
-    let app = Arc::new(App::new(config.clone()));
-    
+    let app = Arc::new(App::new(config.clone(), Arc::clone(&metrics)));
+
     // Start the proxy server in a separate task
     let proxy_handle = {
         let app = Arc::clone(&app);
         tokio::spawn(async move {
-            let proxy = ProxyServer::new(config, tx);
+            let proxy = ProxyServer::new(config, tx, metrics);
             if let Err(e) = proxy.run().await {
                 warn!("Proxy server error: {}", e);
                 app.set_state(AppState::Error(e.to_string()));
@@ -1,12 +1,17 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, OpenOptions};
+use std::io::Write as _;
+use std::path::PathBuf;
 use std::sync::Arc;
 use std::time::Duration;
 use std::str::FromStr;
 
 use anyhow::{Context, Result};
+use base64::{engine::general_purpose::STANDARD, Engine as _};
 use axum::{
     body::Body,
-    extract::{Path, Query, State},
+    extract::ws::{Message, WebSocket, WebSocketUpgrade},
+    extract::{FromRequestParts, Path, Query, State},
     http::{HeaderMap, Method, Request, Response, StatusCode},
     response::IntoResponse,
     routing::{get, post},
@@ -14,14 +19,19 @@ use axum::{
 };
 use chrono;
 use cookie::{Cookie, CookieJar};
+use futures_util::{SinkExt, StreamExt};
 use hyper::body::Bytes;
 use serde::{Deserialize, Serialize};
-use tokio::sync::{mpsc, RwLock};
+use tokio::sync::{mpsc, oneshot, RwLock};
+use tokio_tungstenite::connect_async;
+use tokio_tungstenite::tungstenite::Message as TungMessage;
 use tower::ServiceExt;
 use tower_http::trace::TraceLayer;
 use tracing::{debug, info, warn};
 use uuid::Uuid;
 
+use crate::app::Metrics;
+use crate::auth::{verify_request, VerifyOutcome};
 use crate::config::Config;
 
 const QUEUE_METHODS: [Method; 4] = [
@@ -31,45 +41,250 @@ const QUEUE_METHODS: [Method; 4] = [
     Method::DELETE,
 ];
 
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 struct LogEntry {
     request_id: String,
+    /// Canonical execution order assigned by the queue worker. `None` for
+    /// non-mutating requests, which bypass the queue and run concurrently.
+    sequence: Option<u64>,
     method: String,
     url: String,
     headers: HashMap<String, String>,
     body: Option<String>,
     timestamp: String,
+    /// Whether the claimed identity was cryptographically verified. `None`
+    /// when auth is off and the `dev-address` cookie is trusted as-is.
+    authenticated: Option<bool>,
+    /// Address recovered from the request signature, when verification ran.
+    recovered_address: Option<String>,
+    /// Effective identity forwarded as `x-address` (verified address or the
+    /// `dev-address` cookie). Drives the `/_chopin/logs` address filter.
+    #[serde(default)]
+    address: Option<String>,
+    /// Identifies the chopd session that produced this entry, so replay can
+    /// reproduce a single session from the append-only multi-session log.
+    #[serde(default)]
+    session_id: String,
     response: Option<ResponseLog>,
     contexts: Option<Vec<String>>,
+    /// Present only for WebSocket tunnel records (open/close events).
+    websocket: Option<WebSocketLog>,
 }
 
-#[derive(Debug, Clone, Serialize)]
+/// Records the lifecycle of a spliced WebSocket tunnel. One entry is logged
+/// when the tunnel opens and another when it closes, the latter carrying the
+/// number of frames relayed in each direction.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct WebSocketLog {
+    /// `"open"` or `"close"`.
+    event: String,
+    /// Frames relayed from the client to the upstream dev server.
+    frames_sent: u64,
+    /// Frames relayed from the upstream dev server back to the client.
+    frames_received: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 struct ResponseLog {
     status: u16,
     headers: HashMap<String, String>,
     body: Option<String>,
 }
 
+/// An inbound request delivered over the relay tunnel, to be serviced through
+/// the local proxy just like a directly-connected client.
+#[derive(Debug, Deserialize)]
+struct RelayRequest {
+    id: String,
+    method: String,
+    path: String,
+    #[serde(default)]
+    headers: HashMap<String, String>,
+    #[serde(default)]
+    body: Option<String>,
+}
+
+/// The response to a [`RelayRequest`], streamed back over the same tunnel. The
+/// body is base64-encoded so binary or compressed upstream responses survive
+/// the round-trip intact, matching the local proxy's fidelity.
+#[derive(Debug, Serialize)]
+struct RelayResponse {
+    id: String,
+    status: u16,
+    headers: HashMap<String, String>,
+    body_b64: String,
+}
+
+impl RelayResponse {
+    /// Builds a `502`-style error response to return over the tunnel when the
+    /// request could not be serviced locally.
+    fn error(id: String, message: String) -> Self {
+        Self {
+            id,
+            status: StatusCode::BAD_GATEWAY.as_u16(),
+            headers: HashMap::new(),
+            body_b64: STANDARD.encode(message.as_bytes()),
+        }
+    }
+}
+
+/// Credentials presented to the relay when the tunnel is established.
+#[derive(Debug, Serialize)]
+struct RelayAuth<'a> {
+    #[serde(rename = "type")]
+    kind: &'a str,
+    token: &'a str,
+}
+
+/// A mutating request waiting for its turn in the ordered execution queue.
+///
+/// The worker grants the turn by sending the assigned sequence number over
+/// `turn_tx`; the handler holds the sender paired with `done_rx` until it has finished
+/// forwarding the request and its `report-context` callbacks have settled,
+/// at which point the worker releases the next job.
+struct QueuedJob {
+    turn_tx: oneshot::Sender<u64>,
+    done_rx: oneshot::Receiver<()>,
+}
+
 #[derive(Clone)]
 pub struct ProxyServer {
     config: Config,
     logs: Arc<RwLock<Vec<LogEntry>>>,
     contexts: Arc<RwLock<HashMap<String, Vec<String>>>>,
     tx: mpsc::Sender<String>,
+    queue_tx: mpsc::Sender<QueuedJob>,
+    /// Shared HTTP client reused across requests for connection pooling and
+    /// keep-alive, configured with the upstream timeouts from [`Config`].
+    client: reqwest::Client,
+    metrics: Arc<Metrics>,
+    /// Unique id for this process run, stamped on every persisted entry.
+    session_id: String,
+    /// Nonces seen on verified requests, mapped to the timestamp they carried,
+    /// so a captured `(x-signature, x-nonce)` pair cannot be replayed. Pruned
+    /// as entries age past [`NONCE_WINDOW_MS`].
+    nonces: Arc<RwLock<HashMap<String, i64>>>,
 }
 
+/// How many times an `ECONNREFUSED` is retried before giving up, covering the
+/// window where the target server is still booting (`Starting`).
+const CONNECT_MAX_RETRIES: u32 = 5;
+
+/// Append-only JSONL log of every finalized request, kept under `.chopin/` so a
+/// session can be inspected or replayed after exit.
+const LOG_PATH: &str = ".chopin/requests.jsonl";
+
+/// Polling interval used while waiting for `report-context` callbacks to settle.
+const CONTEXT_SETTLE_POLL: Duration = Duration::from_millis(50);
+
+/// Upper bound on how long a queued request waits for late callbacks.
+const CONTEXT_SETTLE_MAX: Duration = Duration::from_millis(1_000);
+
+/// Freshness window (ms) for the `x-nonce` timestamp. A signed request is only
+/// honored if its nonce lies within this window of the proxy's clock, and each
+/// nonce is accepted once; together these bound replay to the window's width.
+const NONCE_WINDOW_MS: i64 = 60_000;
+
 impl ProxyServer {
-    pub fn new(config: Config, tx: mpsc::Sender<String>) -> Self {
+    pub fn new(config: Config, tx: mpsc::Sender<String>, metrics: Arc<Metrics>) -> Self {
+        let (queue_tx, queue_rx) = mpsc::channel(100);
+        tokio::spawn(Self::queue_worker(queue_rx));
+
+        let client = reqwest::Client::builder()
+            .pool_max_idle_per_host(32)
+            .connect_timeout(Duration::from_millis(config.connect_timeout))
+            .timeout(Duration::from_millis(config.request_timeout))
+            .build()
+            .unwrap_or_else(|_| reqwest::Client::new());
+
         Self {
             config,
             logs: Arc::new(RwLock::new(Vec::new())),
             contexts: Arc::new(RwLock::new(HashMap::new())),
             tx,
+            queue_tx,
+            client,
+            metrics,
+            session_id: Uuid::new_v4().to_string(),
+            nonces: Arc::new(RwLock::new(HashMap::new())),
+        }
+    }
+
+    /// Enforces anti-replay on a verified request's nonce. The nonce is the
+    /// client's millisecond timestamp: it must parse, fall within
+    /// [`NONCE_WINDOW_MS`] of the proxy clock, and not have been seen before.
+    /// Expired nonces are pruned whenever a nonce passes the freshness check,
+    /// so the set stays bounded.
+    async fn check_nonce(&self, nonce: Option<&str>) -> Result<(), String> {
+        let nonce = nonce.ok_or_else(|| "missing nonce".to_string())?;
+        let ts: i64 = nonce
+            .parse()
+            .map_err(|_| "nonce is not a timestamp".to_string())?;
+        let now = chrono::Utc::now().timestamp_millis();
+        if (now - ts).abs() > NONCE_WINDOW_MS {
+            return Err("nonce outside freshness window".to_string());
+        }
+
+        let mut seen = self.nonces.write().await;
+        seen.retain(|_, t| (now - *t).abs() <= NONCE_WINDOW_MS);
+        if seen.insert(nonce.to_string(), ts).is_some() {
+            return Err("nonce already used".to_string());
+        }
+        Ok(())
+    }
+
+    /// Serializes mutating requests so they execute in a deterministic order,
+    /// one at a time, like sequential block/tx execution. Each job is assigned
+    /// a monotonically increasing sequence number and is not released until the
+    /// previous one has fully settled. The loop exits once every `queue_tx`
+    /// handle is dropped, draining any in-flight jobs first.
+    async fn queue_worker(mut rx: mpsc::Receiver<QueuedJob>) {
+        let mut sequence: u64 = 0;
+        while let Some(job) = rx.recv().await {
+            sequence += 1;
+            // If the handler vanished before its turn, skip to the next job.
+            if job.turn_tx.send(sequence).is_err() {
+                continue;
+            }
+            // Block until the handler reports completion (or drops the sender),
+            // ensuring oracle/context data is gathered before the next job runs.
+            let _ = job.done_rx.await;
+        }
+        debug!("Queue worker drained; shutting down");
+    }
+
+    /// Waits for the `report-context` callbacks tied to `request_id` to settle:
+    /// it returns once the collected count stays unchanged across a poll
+    /// interval, or once [`CONTEXT_SETTLE_MAX`] elapses. This gives callbacks
+    /// that land around response time a chance to arrive before the queue
+    /// releases the next mutating request.
+    async fn await_contexts_settle(&self, request_id: &str) {
+        let count = || async {
+            self.contexts
+                .read()
+                .await
+                .get(request_id)
+                .map(|c| c.len())
+                .unwrap_or(0)
+        };
+        let start = tokio::time::Instant::now();
+        let mut last = count().await;
+        loop {
+            tokio::time::sleep(CONTEXT_SETTLE_POLL).await;
+            let now = count().await;
+            if now == last || start.elapsed() >= CONTEXT_SETTLE_MAX {
+                break;
+            }
+            last = now;
         }
     }
 
     pub async fn run(self) -> Result<()> {
         let port = self.config.proxy_port;
+        let relay_url = self.config.relay_url.clone();
+        let relay_token = self.config.relay_token.clone();
+        let metrics = Arc::clone(&self.metrics);
+
         let app = Router::new()
             .route("/_chopin/login", get(Self::handle_login))
             .route("/_chopin/report-context", post(Self::handle_report_context))
@@ -78,15 +293,127 @@ impl ProxyServer {
             .layer(TraceLayer::new_for_http())
             .with_state(Arc::new(self));
 
+        // When a relay is configured, dial out and serve inbound requests over
+        // that tunnel through the very same router (identity injection, queueing
+        // and logging all apply), in addition to the local listener.
+        if let Some(relay_url) = relay_url {
+            let router = app.clone();
+            tokio::spawn(async move {
+                Self::run_relay(router, relay_url, relay_token, metrics).await;
+            });
+        }
+
         let addr = format!("127.0.0.1:{}", port);
         info!("Starting proxy server on {}", addr);
-        
+
         let listener = tokio::net::TcpListener::bind(&addr).await?;
         axum::serve(listener, app).await?;
 
         Ok(())
     }
 
+    /// Keeps a relay tunnel open, reconnecting with exponential backoff whenever
+    /// the connection drops.
+    async fn run_relay(
+        router: Router,
+        relay_url: String,
+        relay_token: Option<String>,
+        metrics: Arc<Metrics>,
+    ) {
+        let mut backoff = Duration::from_millis(500);
+        loop {
+            match Self::serve_relay(&router, &relay_url, relay_token.as_deref(), &metrics).await {
+                Ok(()) => info!("Relay connection closed; reconnecting"),
+                Err(e) => warn!("Relay connection error: {}; reconnecting", e),
+            }
+            metrics.set_relay_connected(false);
+            tokio::time::sleep(backoff).await;
+            backoff = (backoff * 2).min(Duration::from_secs(30));
+        }
+    }
+
+    /// Opens a single relay connection, authenticates, and services inbound
+    /// requests until the socket closes.
+    async fn serve_relay(
+        router: &Router,
+        relay_url: &str,
+        relay_token: Option<&str>,
+        metrics: &Arc<Metrics>,
+    ) -> Result<()> {
+        let (ws, _resp) = connect_async(relay_url)
+            .await
+            .with_context(|| format!("Failed to dial relay {}", relay_url))?;
+        let (mut sink, mut stream) = ws.split();
+
+        if let Some(token) = relay_token {
+            let auth = RelayAuth { kind: "auth", token };
+            sink.send(TungMessage::Text(serde_json::to_string(&auth)?.into()))
+                .await?;
+        }
+
+        metrics.set_relay_connected(true);
+        info!("Relay tunnel established to {}", relay_url);
+
+        while let Some(msg) = stream.next().await {
+            let text = match msg? {
+                TungMessage::Text(text) => text,
+                TungMessage::Close(_) => break,
+                _ => continue,
+            };
+            let request: RelayRequest = match serde_json::from_str(&text) {
+                Ok(req) => req,
+                Err(e) => {
+                    warn!("Malformed relay request: {}", e);
+                    continue;
+                }
+            };
+            let response = Self::dispatch_relay(router.clone(), request).await;
+            sink.send(TungMessage::Text(serde_json::to_string(&response)?.into()))
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Routes a relayed request through the local router and captures the
+    /// response to ship back over the tunnel.
+    async fn dispatch_relay(router: Router, request: RelayRequest) -> RelayResponse {
+        let id = request.id.clone();
+        let mut builder = Request::builder()
+            .method(request.method.as_str())
+            .uri(&request.path);
+        for (name, value) in &request.headers {
+            builder = builder.header(name, value);
+        }
+        let http_req = match builder.body(Body::from(request.body.unwrap_or_default())) {
+            Ok(req) => req,
+            Err(e) => return RelayResponse::error(id, format!("bad relay request: {}", e)),
+        };
+
+        let response = match router.oneshot(http_req).await {
+            Ok(resp) => resp,
+            Err(e) => return RelayResponse::error(id, format!("routing failed: {}", e)),
+        };
+
+        let status = response.status().as_u16();
+        let headers = response
+            .headers()
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_str().unwrap_or("").to_string()))
+            .collect();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .map(|b| STANDARD.encode(&b))
+            .unwrap_or_default();
+
+        RelayResponse {
+            id,
+            status,
+            headers,
+            body_b64: body,
+        }
+    }
+
     async fn handle_login(
         Query(params): Query<HashMap<String, String>>,
     ) -> impl IntoResponse {
@@ -126,12 +453,32 @@ impl ProxyServer {
 
     async fn handle_logs(
         State(state): State<Arc<Self>>,
+        Query(params): Query<HashMap<String, String>>,
     ) -> impl IntoResponse {
         let logs = state.logs.read().await;
         let contexts = state.contexts.read().await;
 
+        // Optional filters keep large persisted logs navigable.
+        let address = params.get("address");
+        let method = params.get("method");
+        let seq_min = params.get("seqMin").and_then(|s| s.parse::<u64>().ok());
+        let seq_max = params.get("seqMax").and_then(|s| s.parse::<u64>().ok());
+
+        // Merge the persisted log (earlier sessions, survives restarts) with the
+        // in-memory view. The in-memory entry wins on `request_id` so the current
+        // session isn't double-counted.
+        let live_ids: HashSet<&str> = logs.iter().map(|l| l.request_id.as_str()).collect();
+        let persisted = read_persisted_entries();
+
         let mut response = Vec::new();
-        for log in logs.iter() {
+        let merged = persisted
+            .iter()
+            .filter(|l| !live_ids.contains(l.request_id.as_str()))
+            .chain(logs.iter());
+        for log in merged {
+            if !log_matches(log, method, address, seq_min, seq_max) {
+                continue;
+            }
             let mut log = log.clone();
             if let Some(ctx) = contexts.get(&log.request_id) {
                 log.contexts = Some(ctx.clone());
@@ -153,6 +500,25 @@ impl ProxyServer {
         let is_queued = QUEUE_METHODS.contains(&method);
         let request_id = Uuid::new_v4().to_string();
 
+        // WebSocket upgrades can't be buffered like ordinary HTTP: hand them off
+        // to a bidirectional splice instead of the request/response path.
+        if is_websocket_upgrade(&headers) {
+            let (mut parts, _body) = req.into_parts();
+            return match WebSocketUpgrade::from_request_parts(&mut parts, &()).await {
+                Ok(ws) => Self::handle_websocket(
+                    Arc::clone(&state),
+                    ws,
+                    state.config.target_port,
+                    uri,
+                    request_id,
+                ),
+                Err(err) => {
+                    warn!("WebSocket upgrade rejected: {}", err);
+                    err.into_response()
+                }
+            };
+        }
+
         // Process cookies and extract dev-address
         let mut x_address = None;
         if let Some(cookie_str) = headers
@@ -177,9 +543,77 @@ impl ProxyServer {
             Err(_) => Bytes::new(),
         };
 
+        // Verify the claimed identity before trusting it. In strict mode every
+        // proxied request must carry a valid signature or it is rejected with
+        // `401`. Off mode never verifies and trusts the `dev-address` cookie.
+        let header_str = |name: &str| {
+            headers
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .map(String::from)
+        };
+        let outcome = verify_request(
+            &state.config.auth,
+            x_address.as_deref(),
+            method.as_str(),
+            &uri,
+            &body_bytes,
+            header_str("x-signature").as_deref(),
+            header_str("x-nonce").as_deref(),
+        );
+        let (authenticated, recovered_address) = match outcome {
+            VerifyOutcome::Skipped => (None, None),
+            VerifyOutcome::Verified { address } => {
+                // A valid signature proves authenticity but not freshness;
+                // reject replays of a captured signature/nonce pair.
+                if let Err(reason) = state.check_nonce(header_str("x-nonce").as_deref()).await {
+                    warn!("Rejected replayed request: {}", reason);
+                    return Response::builder()
+                        .status(StatusCode::UNAUTHORIZED)
+                        .body(Body::from(format!("Unauthorized: {}", reason)))
+                        .unwrap();
+                }
+                x_address = Some(address.clone());
+                (Some(true), Some(address))
+            }
+            VerifyOutcome::Rejected { reason } => {
+                warn!("Rejected unauthenticated request: {}", reason);
+                return Response::builder()
+                    .status(StatusCode::UNAUTHORIZED)
+                    .body(Body::from(format!("Unauthorized: {}", reason)))
+                    .unwrap();
+            }
+        };
+
+        // Mutating requests are serialized through the execution queue: acquire
+        // a turn (and the canonical sequence number) before touching the target.
+        // `done_tx` is held until this handler finishes so the worker knows when
+        // the request has settled. GET/HEAD requests skip the queue entirely.
+        let mut done_tx = None;
+        let sequence = if is_queued {
+            let (turn_tx, turn_rx) = oneshot::channel();
+            let (done_sender, done_rx) = oneshot::channel();
+            if state
+                .queue_tx
+                .send(QueuedJob { turn_tx, done_rx })
+                .await
+                .is_err()
+            {
+                warn!("Queue worker unavailable; forwarding without ordering");
+                None
+            } else {
+                done_tx = Some(done_sender);
+                turn_rx.await.ok()
+            }
+        } else {
+            None
+        };
+
         // Create log entry
+        let effective_address = x_address.clone();
         let mut log_entry = LogEntry {
             request_id: request_id.clone(),
+            sequence,
             method: method.to_string(),
             url: uri,
             headers: headers
@@ -188,8 +622,13 @@ impl ProxyServer {
                 .collect(),
             body: Some(String::from_utf8_lossy(&body_bytes).to_string()),
             timestamp: chrono::Utc::now().to_rfc3339(),
+            authenticated,
+            recovered_address,
+            address: effective_address,
+            session_id: state.session_id.clone(),
             response: None,
             contexts: None,
+            websocket: None,
         };
 
         // Convert headers to reqwest format
@@ -214,7 +653,7 @@ impl ProxyServer {
 
         if is_queued {
             let callback_url = format!(
-                "http://localhost:{}/chopin/report-context?requestId={}",
+                "http://localhost:{}/_chopin/report-context?requestId={}",
                 state.config.proxy_port, request_id
             );
             if let Ok(value) = reqwest::header::HeaderValue::from_str(&callback_url) {
@@ -225,8 +664,8 @@ impl ProxyServer {
             }
         }
 
-        // Forward the request
-        let client = reqwest::Client::new();
+        // Forward the request using the shared, pooled client.
+        let client = &state.client;
         let forward_req = client
             .request(reqwest::Method::from_str(method.as_str()).unwrap(), &target_url)
             .headers(forward_headers)
@@ -234,15 +673,45 @@ impl ProxyServer {
             .build()
             .unwrap();
 
-        // Send the request and get response
-        let resp = match client.execute(forward_req).await {
-            Ok(r) => r,
-            Err(e) => {
-                warn!("Proxy error: {}", e);
-                return Response::builder()
-                    .status(StatusCode::BAD_GATEWAY)
-                    .body(Body::from(format!("Proxy error: {}", e)))
-                    .unwrap();
+        // Send the request, retrying a refused connection with backoff so that
+        // requests arriving while the target is still booting succeed. A
+        // timeout maps to `504` and a persistently refused connection to `502`.
+        let mut attempt: u32 = 0;
+        let resp = loop {
+            let attempt_req = forward_req
+                .try_clone()
+                .expect("buffered body is always clonable");
+            match client.execute(attempt_req).await {
+                Ok(r) => break r,
+                Err(e) if e.is_timeout() => {
+                    state.metrics.record_timeout();
+                    warn!("Upstream timeout: {}", e);
+                    return Response::builder()
+                        .status(StatusCode::GATEWAY_TIMEOUT)
+                        .body(Body::from(format!("Gateway timeout: {}", e)))
+                        .unwrap();
+                }
+                Err(e) if e.is_connect() && attempt < CONNECT_MAX_RETRIES => {
+                    attempt += 1;
+                    state.metrics.record_retry();
+                    let backoff = Duration::from_millis(100 * 2u64.pow(attempt));
+                    debug!("Connection refused; retry {} after {:?}", attempt, backoff);
+                    tokio::time::sleep(backoff).await;
+                }
+                Err(e) if e.is_connect() => {
+                    warn!("Upstream connection refused: {}", e);
+                    return Response::builder()
+                        .status(StatusCode::BAD_GATEWAY)
+                        .body(Body::from(format!("Bad gateway: {}", e)))
+                        .unwrap();
+                }
+                Err(e) => {
+                    warn!("Proxy error: {}", e);
+                    return Response::builder()
+                        .status(StatusCode::BAD_GATEWAY)
+                        .body(Body::from(format!("Proxy error: {}", e)))
+                        .unwrap();
+                }
             }
         };
 
@@ -260,9 +729,29 @@ impl ProxyServer {
             body: Some(String::from_utf8_lossy(&body).to_string()),
         });
 
+        // For queued requests, give report-context callbacks a brief window to
+        // settle before finalizing, so context reported around response time
+        // isn't raced or lost. Non-queued requests have no callback contract.
+        if is_queued {
+            state.await_contexts_settle(&request_id).await;
+        }
+
+        // Attach any collected contexts, then persist the finalized entry to the
+        // append-only log before it joins the in-memory view.
+        if let Some(ctx) = state.contexts.read().await.get(&request_id) {
+            log_entry.contexts = Some(ctx.clone());
+        }
+        state.persist_entry(&log_entry);
+
         // Store log entry
         state.logs.write().await.push(log_entry);
 
+        // Release the queue: the request has settled and its contexts are in,
+        // so the worker may hand the turn to the next mutating request.
+        if let Some(done_tx) = done_tx.take() {
+            let _ = done_tx.send(());
+        }
+
         // Convert response headers to axum format
         let mut response = Response::builder().status(status);
         for (k, v) in headers.iter() {
@@ -274,4 +763,354 @@ impl ProxyServer {
         }
         response.body(Body::from(body)).unwrap()
     }
-} 
\ No newline at end of file
+
+    /// Upgrades the client connection and splices it to an upstream WebSocket on
+    /// the target dev server, so HMR, live reload, and subscription traffic flow
+    /// through chopd unchanged.
+    fn handle_websocket(
+        state: Arc<Self>,
+        ws: WebSocketUpgrade,
+        target_port: u16,
+        uri: String,
+        request_id: String,
+    ) -> Response<Body> {
+        let upstream_url = format!("ws://localhost:{}{}", target_port, uri);
+        ws.on_upgrade(move |client| async move {
+            Self::splice_websocket(state, client, upstream_url, request_id).await;
+        })
+    }
+
+    /// Opens the upstream socket and relays frames in both directions until
+    /// either side closes, logging the tunnel open/close with frame counts.
+    async fn splice_websocket(
+        state: Arc<Self>,
+        client: WebSocket,
+        upstream_url: String,
+        request_id: String,
+    ) {
+        let upstream = match connect_async(&upstream_url).await {
+            Ok((stream, _resp)) => stream,
+            Err(e) => {
+                warn!("Failed to open upstream WebSocket {}: {}", upstream_url, e);
+                return;
+            }
+        };
+
+        state
+            .push_ws_log(&request_id, &upstream_url, WebSocketLog {
+                event: "open".to_string(),
+                frames_sent: 0,
+                frames_received: 0,
+            })
+            .await;
+
+        let (mut client_sink, mut client_stream) = client.split();
+        let (mut upstream_sink, mut upstream_stream) = upstream.split();
+
+        // Client → upstream.
+        let to_upstream = async move {
+            let mut frames = 0u64;
+            while let Some(Ok(msg)) = client_stream.next().await {
+                frames += 1;
+                if upstream_sink.send(axum_to_tungstenite(msg)).await.is_err() {
+                    break;
+                }
+            }
+            let _ = upstream_sink.close().await;
+            frames
+        };
+
+        // Upstream → client.
+        let to_client = async move {
+            let mut frames = 0u64;
+            while let Some(Ok(msg)) = upstream_stream.next().await {
+                frames += 1;
+                if let Some(msg) = tungstenite_to_axum(msg) {
+                    if client_sink.send(msg).await.is_err() {
+                        break;
+                    }
+                }
+            }
+            let _ = client_sink.close().await;
+            frames
+        };
+
+        let (frames_sent, frames_received) = tokio::join!(to_upstream, to_client);
+
+        state
+            .push_ws_log(&request_id, &upstream_url, WebSocketLog {
+                event: "close".to_string(),
+                frames_sent,
+                frames_received,
+            })
+            .await;
+    }
+
+    /// Records a WebSocket tunnel lifecycle event in the shared log.
+    async fn push_ws_log(&self, request_id: &str, url: &str, websocket: WebSocketLog) {
+        let entry = LogEntry {
+            request_id: request_id.to_string(),
+            sequence: None,
+            method: "WEBSOCKET".to_string(),
+            url: url.to_string(),
+            headers: HashMap::new(),
+            body: None,
+            timestamp: chrono::Utc::now().to_rfc3339(),
+            authenticated: None,
+            recovered_address: None,
+            address: None,
+            session_id: self.session_id.clone(),
+            response: None,
+            contexts: None,
+            websocket: Some(websocket),
+        };
+        self.persist_entry(&entry);
+        self.logs.write().await.push(entry);
+    }
+
+    /// Appends a finalized entry to the persistent JSONL log, flushing as each
+    /// request completes. Persistence failures are logged but never fatal.
+    fn persist_entry(&self, entry: &LogEntry) {
+        let line = match serde_json::to_string(entry) {
+            Ok(line) => line,
+            Err(e) => {
+                warn!("Failed to serialize log entry: {}", e);
+                return;
+            }
+        };
+        if let Err(e) = append_log_line(&line) {
+            warn!("Failed to persist log entry: {}", e);
+        }
+    }
+
+    /// Deterministically replays the most recently recorded session: the
+    /// append-only log spans multiple runs and the per-session `sequence`
+    /// restarts at 1 each time, so replaying the whole file would interleave
+    /// sessions. We isolate the last session (the one that produced the final
+    /// record) and re-issue its mutating requests in `sequence` order, reusing
+    /// the recorded identities and context callbacks.
+    pub async fn replay(config: &Config) -> Result<()> {
+        let all = read_persisted_entries();
+        let session_id = all
+            .last()
+            .map(|entry| entry.session_id.clone())
+            .unwrap_or_default();
+
+        let mut entries: Vec<LogEntry> = all
+            .into_iter()
+            .filter(|entry| entry.session_id == session_id)
+            .filter(|entry| {
+                Method::from_str(&entry.method)
+                    .map(|m| QUEUE_METHODS.contains(&m))
+                    .unwrap_or(false)
+            })
+            .collect();
+        entries.sort_by_key(|entry| entry.sequence.unwrap_or(0));
+
+        // Stand up a minimal listener for the `x-callback-url` the replayed
+        // requests are told to report context to. Without the full proxy
+        // running, these callbacks would otherwise hit a dead port; collecting
+        // them lets replay surface the same oracle/context data a live run did.
+        let collected: Arc<RwLock<HashMap<String, Vec<String>>>> =
+            Arc::new(RwLock::new(HashMap::new()));
+        let callback_app = Router::new()
+            .route(
+                "/_chopin/report-context",
+                post(Self::collect_replay_context),
+            )
+            .with_state(Arc::clone(&collected));
+        let addr = format!("127.0.0.1:{}", config.proxy_port);
+        let listener = tokio::net::TcpListener::bind(&addr)
+            .await
+            .with_context(|| format!("Failed to bind replay callback listener on {}", addr))?;
+        let server = tokio::spawn(async move {
+            let _ = axum::serve(listener, callback_app).await;
+        });
+
+        let client = reqwest::Client::builder()
+            .connect_timeout(Duration::from_millis(config.connect_timeout))
+            .timeout(Duration::from_millis(config.request_timeout))
+            .build()
+            .unwrap_or_else(|_| reqwest::Client::new());
+
+        info!("Replaying {} mutating request(s)", entries.len());
+        for entry in entries {
+            let method = match reqwest::Method::from_str(&entry.method) {
+                Ok(m) => m,
+                Err(_) => continue,
+            };
+            let url = format!("http://localhost:{}{}", config.target_port, entry.url);
+            let mut req = client.request(method, &url);
+
+            // Reuse the recorded identity so replayed requests act as the same
+            // address they originally did.
+            if let Some(addr) = replay_address(&entry) {
+                req = req.header("x-address", addr);
+            }
+            // Point context callbacks at the replay listener on the proxy port,
+            // mirroring live traffic.
+            let callback_url = format!(
+                "http://localhost:{}/_chopin/report-context?requestId={}",
+                config.proxy_port, entry.request_id
+            );
+            req = req.header("x-callback-url", callback_url);
+            if let Some(body) = entry.body.clone() {
+                req = req.body(body);
+            }
+
+            info!("Replaying seq {:?} {} {}", entry.sequence, entry.method, entry.url);
+            if let Err(e) = req.send().await {
+                warn!("Replay of {} {} failed: {}", entry.method, entry.url, e);
+            }
+        }
+
+        // Give any callbacks dispatched around the final response a moment to
+        // arrive before tearing the listener down, then report what we saw.
+        tokio::time::sleep(CONTEXT_SETTLE_MAX).await;
+        server.abort();
+        let total: usize = collected.read().await.values().map(|c| c.len()).sum();
+        info!("Collected {} replayed context callback(s)", total);
+
+        Ok(())
+    }
+
+    /// Collects a `report-context` callback during replay, keyed by the
+    /// `requestId` the replayed request was issued under. Mirrors
+    /// [`Self::handle_report_context`] but writes into the replay-local map.
+    async fn collect_replay_context(
+        State(collected): State<Arc<RwLock<HashMap<String, Vec<String>>>>>,
+        Query(params): Query<HashMap<String, String>>,
+        body: String,
+    ) -> impl IntoResponse {
+        let request_id = match params.get("requestId") {
+            Some(id) => id,
+            None => return (StatusCode::BAD_REQUEST, "Missing requestId").into_response(),
+        };
+        collected
+            .write()
+            .await
+            .entry(request_id.to_string())
+            .or_insert_with(Vec::new)
+            .push(body);
+        StatusCode::OK.into_response()
+    }
+}
+
+/// Appends a single JSONL record to the persistent log, creating `.chopin/` and
+/// the log file on first write.
+fn append_log_line(line: &str) -> std::io::Result<()> {
+    if let Some(parent) = PathBuf::from(LOG_PATH).parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut file = OpenOptions::new().create(true).append(true).open(LOG_PATH)?;
+    writeln!(file, "{}", line)
+}
+
+/// Reads the persisted JSONL log into entries, preserving file order. Malformed
+/// lines are skipped, and a missing file yields an empty list rather than an
+/// error.
+fn read_persisted_entries() -> Vec<LogEntry> {
+    match fs::read_to_string(LOG_PATH) {
+        Ok(content) => content
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .filter_map(|line| serde_json::from_str(line).ok())
+            .collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+/// Applies the `/_chopin/logs` query filters to a single entry.
+fn log_matches(
+    log: &LogEntry,
+    method: Option<&String>,
+    address: Option<&String>,
+    seq_min: Option<u64>,
+    seq_max: Option<u64>,
+) -> bool {
+    if let Some(method) = method {
+        if !log.method.eq_ignore_ascii_case(method) {
+            return false;
+        }
+    }
+    if let Some(address) = address {
+        // Match against the effective identity, falling back to the recovered
+        // address, so ordinary cookie/dev-address traffic is findable too.
+        let effective = log.address.as_deref().or(log.recovered_address.as_deref());
+        if !effective
+            .map(|a| a.eq_ignore_ascii_case(address))
+            .unwrap_or(false)
+        {
+            return false;
+        }
+    }
+    match log.sequence {
+        Some(seq) => {
+            if seq_min.map(|min| seq < min).unwrap_or(false)
+                || seq_max.map(|max| seq > max).unwrap_or(false)
+            {
+                return false;
+            }
+        }
+        // A sequence-range filter excludes non-sequenced (GET/HEAD) logs.
+        None => {
+            if seq_min.is_some() || seq_max.is_some() {
+                return false;
+            }
+        }
+    }
+    true
+}
+
+/// Resolves the identity to reuse when replaying an entry: the effective
+/// forwarded address if recorded, otherwise the recovered address, otherwise
+/// the `dev-address` cookie from the recording.
+fn replay_address(entry: &LogEntry) -> Option<String> {
+    if let Some(addr) = &entry.address {
+        return Some(addr.clone());
+    }
+    if let Some(addr) = &entry.recovered_address {
+        return Some(addr.clone());
+    }
+    let cookie_str = entry.headers.get("cookie")?;
+    cookie::Cookie::split_parse(cookie_str)
+        .filter_map(Result::ok)
+        .find(|c| c.name() == "dev-address")
+        .map(|c| c.value().to_string())
+}
+
+/// Returns `true` when the request is a WebSocket upgrade handshake.
+fn is_websocket_upgrade(headers: &HeaderMap) -> bool {
+    let header_contains = |name: &str, needle: &str| {
+        headers
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .map(|v| v.to_ascii_lowercase().contains(needle))
+            .unwrap_or(false)
+    };
+    header_contains("connection", "upgrade") && header_contains("upgrade", "websocket")
+}
+
+/// Translates an axum frame into its tungstenite equivalent for the upstream.
+fn axum_to_tungstenite(msg: Message) -> TungMessage {
+    match msg {
+        Message::Text(text) => TungMessage::Text(text.into()),
+        Message::Binary(data) => TungMessage::Binary(data.into()),
+        Message::Ping(data) => TungMessage::Ping(data.into()),
+        Message::Pong(data) => TungMessage::Pong(data.into()),
+        Message::Close(_) => TungMessage::Close(None),
+    }
+}
+
+/// Translates an upstream tungstenite frame back into an axum frame. Control
+/// frames that axum manages internally (raw `Frame`) are dropped.
+fn tungstenite_to_axum(msg: TungMessage) -> Option<Message> {
+    match msg {
+        TungMessage::Text(text) => Some(Message::Text(text.to_string().into())),
+        TungMessage::Binary(data) => Some(Message::Binary(data.to_vec().into())),
+        TungMessage::Ping(data) => Some(Message::Ping(data.to_vec().into())),
+        TungMessage::Pong(data) => Some(Message::Pong(data.to_vec().into())),
+        TungMessage::Close(_) => Some(Message::Close(None)),
+        TungMessage::Frame(_) => None,
+    }
+}
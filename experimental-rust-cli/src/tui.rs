@@ -35,12 +35,23 @@ pub mod ui {
         };
 
         let config = app.config();
+        let metrics = app.metrics();
+        let relay_status = if config.relay_url.is_none() {
+            "off"
+        } else if metrics.relay_connected() {
+            "connected"
+        } else {
+            "connecting"
+        };
         let status = format!(
-            "Status: {} | Proxy: :{} → :{} | Requests: {}",
+            "Status: {} | Proxy: :{} → :{} | Requests: {} | Retries: {} | Timeouts: {} | Relay: {}",
             status_text,
             config.proxy_port,
             config.target_port,
-            app.get_request_count()
+            app.get_request_count(),
+            metrics.retries(),
+            metrics.timeouts(),
+            relay_status,
         );
 
         let text = vec![Line::from(vec![